@@ -0,0 +1,173 @@
+//! An intermediate, serializable representation of a MIR basic-block graph,
+//! modeled on rustc's `gsgdt` crate. Building this structured model before
+//! emitting DOT means the same data can also be served as JSON (see
+//! `render::graphviz::render_mir_json`) or diffed against the graph from the
+//! previous execution step to see which blocks/statements just changed.
+
+use serde::Serialize;
+use std::fmt::{self, Write};
+
+/// A graphviz-renderable (and serde-serializable) view of a `Body`.
+#[derive(Clone, Serialize)]
+pub struct Graph {
+    pub name: String,
+    pub kind: GraphKind,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    /// A stable identifier for the `mir.source` (instance + promoted index)
+    /// this graph was built from. Block labels are just `bbN` indices, so
+    /// without this `diff` would happily pair up same-numbered blocks from
+    /// two entirely different bodies (e.g. stepping from a caller into a
+    /// callee) and report bogus changed statements instead of flagging every
+    /// block as new.
+    pub identity: String,
+}
+
+/// Whether this graph is for a function body or one of its promoted constants.
+#[derive(Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum GraphKind {
+    Body,
+    Promoted(usize),
+}
+
+/// A single basic block, rendered as an HTML-styled DOT table.
+#[derive(Clone, Serialize)]
+pub struct Node {
+    pub label: String,
+    pub style: NodeStyle,
+    pub stmts: Vec<String>,
+    /// One DOM id per entry in `stmts` (see `render::span_map`), set as the
+    /// `ID` attribute graphviz understands on HTML-like label cells and
+    /// carries through into the rendered SVG, so a click handler can match it
+    /// up against the `data-loc` the source pane puts on the same location.
+    pub stmt_ids: Vec<String>,
+}
+
+/// Per-node styling hooks. Currently there is only the default block
+/// appearance, but this gives the diff/highlighting machinery somewhere to
+/// attach e.g. a "changed since last step" style in the future.
+#[derive(Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum NodeStyle {
+    Plain,
+}
+
+impl Default for NodeStyle {
+    fn default() -> Self {
+        NodeStyle::Plain
+    }
+}
+
+/// An edge between two basic blocks, with the successor label rustc computed
+/// (e.g. `true`/`false` for a `SwitchInt`, `unwind` for a cleanup edge).
+#[derive(Clone, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub label: String,
+}
+
+impl Graph {
+    /// Render this graph as a graphviz DOT source string, in the same
+    /// HTML-table-per-node shape `write_node`/`write_edges` used to emit by hand.
+    pub fn to_dot(&self) -> Result<String, fmt::Error> {
+        let mut dot = String::new();
+        writeln!(dot, "digraph {} {{", self.name)?;
+
+        writeln!(dot, r#"    graph [fontname="monospace"];"#)?;
+        writeln!(dot, r#"    node [fontname="monospace"];"#)?;
+        writeln!(dot, r#"    edge [fontname="monospace"];"#)?;
+
+        for node in &self.nodes {
+            write!(dot, r#"    "{}" [shape="none", label=<"#, node.label)?;
+            node.write_label(&mut dot)?;
+            writeln!(dot, ">];")?;
+        }
+
+        for edge in &self.edges {
+            writeln!(
+                dot,
+                r#"    {} -> {} [label="{}"];"#,
+                edge.from, edge.to, edge.label
+            )?;
+        }
+
+        writeln!(dot, "}}")?;
+        Ok(dot)
+    }
+
+    /// Compare this graph (the current step) against the graph from the
+    /// previous execution step, matching nodes by their `bbN` label and
+    /// diffing their statement lists, so the UI can highlight what a single
+    /// step just changed. If `previous` is a different body entirely (its
+    /// `identity` differs), every block is reported as new rather than
+    /// pairing up same-numbered blocks that have nothing to do with each
+    /// other.
+    pub fn diff(&self, previous: &Graph) -> Vec<NodeDiff> {
+        if self.identity != previous.identity {
+            return self
+                .nodes
+                .iter()
+                .map(|node| NodeDiff {
+                    label: node.label.clone(),
+                    is_new: true,
+                    changed_stmts: (0..node.stmts.len()).collect(),
+                })
+                .collect();
+        }
+        self.nodes
+            .iter()
+            .map(|node| match previous.nodes.iter().find(|n| n.label == node.label) {
+                Some(prev_node) => {
+                    let changed_stmts = node
+                        .stmts
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, stmt)| prev_node.stmts.get(*i) != Some(*stmt))
+                        .map(|(i, _)| i)
+                        .collect();
+                    NodeDiff {
+                        label: node.label.clone(),
+                        is_new: false,
+                        changed_stmts,
+                    }
+                }
+                None => NodeDiff {
+                    label: node.label.clone(),
+                    is_new: true,
+                    changed_stmts: (0..node.stmts.len()).collect(),
+                },
+            })
+            .collect()
+    }
+}
+
+impl Node {
+    fn write_label<W: Write>(&self, w: &mut W) -> fmt::Result {
+        write!(w, r#"<table border="0" cellborder="1" cellspacing="0">"#)?;
+
+        write!(
+            w,
+            r#"<tr><td bgcolor="gray" align="center">{}</td></tr>"#,
+            self.label
+        )?;
+
+        for (stmt, id) in self.stmts.iter().zip(&self.stmt_ids) {
+            write!(
+                w,
+                r#"<tr><td ID="{}" align="left" balign="left">{}</td></tr>"#,
+                id, stmt
+            )?;
+        }
+
+        writeln!(w, "</table>")
+    }
+}
+
+/// The result of comparing one node across two steps: which statement
+/// indices (if any) rendered differently, or whether the block is new.
+#[derive(Clone, Serialize)]
+pub struct NodeDiff {
+    pub label: String,
+    pub is_new: bool,
+    pub changed_stmts: Vec<usize>,
+}
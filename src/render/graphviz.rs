@@ -8,15 +8,27 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use crate::render::graph::{Edge, Graph, GraphKind, Node, NodeDiff, NodeStyle};
+use crate::render::rainbow;
+use crate::render::span_map;
 use crate::step::LocalBreakpoints;
 use miri::{Frame, FrameData, Tag};
+use rocket::serde::json::Json;
 use rustc_middle::mir::*;
+use rustc_middle::ty::TyCtxt;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::{self, Debug, Write};
 
-pub fn render_html<'tcx>(frame: &Frame<Tag, FrameData>, breakpoints: LocalBreakpoints) -> String {
+pub fn render_html<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    frame: &Frame<Tag, FrameData>,
+    breakpoints: LocalBreakpoints,
+    rainbow: bool,
+) -> String {
     let mut rendered = String::new();
 
-    render_mir_svg(&frame.body, breakpoints, &mut rendered, None).unwrap();
+    render_mir_svg(tcx, &frame.body, breakpoints, &mut rendered, None, rainbow).unwrap();
 
     let (block, statement_index) = if let Some(location) = frame.loc {
         (location.block, location.statement_index)
@@ -27,19 +39,15 @@ pub fn render_html<'tcx>(frame: &Frame<Tag, FrameData>, breakpoints: LocalBreakp
 
     let (bb, stmt) = {
         let blck = &frame.body.basic_blocks()[block];
-        (
-            block.index() + 1,
-            if statement_index == blck.statements.len() {
-                if blck.statements.is_empty() {
-                    6
-                } else {
-                    blck.statements.len() + 7
-                }
-            } else {
-                assert!(statement_index < blck.statements.len());
-                statement_index + 6
-            },
-        )
+        assert!(statement_index <= blck.statements.len());
+        // Each row in `Node::write_label` - the header plus one `<tr><td>` per
+        // entry in `stmts` (see graph.rs) - renders as a cell-border polygon
+        // followed by the cell's text, so row `i`'s text sits 2 children after
+        // row `i - 1`'s. `6` is the position of row 0's text (after the
+        // <title>, the table's outer polygon, and the header row's own
+        // polygon+text); the terminator is just the row at
+        // `blck.statements.len()`, statement or not.
+        (block.index() + 1, 6 + 2 * statement_index)
     };
     let edge_colors = {
         let blck = &frame.body.basic_blocks()[block];
@@ -100,6 +108,9 @@ pub fn render_html<'tcx>(frame: &Frame<Tag, FrameData>, breakpoints: LocalBreakp
         .edge > path {{
             fill: none;
         }}
+        .linked-highlight {{
+            outline: 2px solid orange;
+        }}
         </style>
         <script>
         {edge_colors}
@@ -109,6 +120,31 @@ pub fn render_html<'tcx>(frame: &Frame<Tag, FrameData>, breakpoints: LocalBreakp
                 el.classList.add("edge-" + edge_colors[title]);
             }}
         }}
+        // Link the MIR statement/terminator cells (identified by the `ID`
+        // graphviz carries through into the SVG) to the source ranges with a
+        // matching `data-loc`, so clicking either highlights both. A single
+        // `data-loc` can carry several space-separated ids (a source range
+        // can cover more than one MIR location), so keys is always an array
+        // and we match any of them with the `~=` "one of these words" selector.
+        function toggleLinked(keys) {{
+            if (!keys || !keys.length) {{ return; }}
+            let selector = keys.map(function(key) {{ return '[data-loc~="' + key + '"]'; }}).join(',');
+            document.querySelectorAll(selector).forEach(function(el) {{
+                el.classList.toggle("linked-highlight");
+            }});
+            keys.forEach(function(key) {{
+                let mirEl = document.getElementById(key);
+                if (mirEl) {{
+                    mirEl.classList.toggle("linked-highlight");
+                }}
+            }});
+        }}
+        document.querySelectorAll("#the_code [data-loc]").forEach(function(el) {{
+            el.addEventListener("click", function() {{ toggleLinked(el.dataset.loc.split(" ")); }});
+        }});
+        for (let el of document.querySelectorAll("#mir > svg [id^='bb']")) {{
+            el.addEventListener("click", function() {{ toggleLinked([el.id]); }});
+        }}
         </script>"##,
             bb,
             stmt,
@@ -118,81 +154,149 @@ pub fn render_html<'tcx>(frame: &Frame<Tag, FrameData>, breakpoints: LocalBreakp
     rendered
 }
 
+thread_local! {
+    // The `Graph` built for the previous execution step, kept around so the next
+    // step's graph can be diffed against it to highlight what just changed.
+    static PREVIOUS_GRAPH: RefCell<Option<Graph>> = RefCell::new(None);
+}
+
 /// Write a graphviz DOT graph of a list of MIRs.
-pub fn render_mir_svg<W: Write>(
+pub fn render_mir_svg<'tcx, W: Write>(
+    tcx: TyCtxt<'tcx>,
     mir: &Body,
     breakpoints: LocalBreakpoints,
     w: &mut W,
     promoted: Option<usize>,
+    rainbow: bool,
 ) -> fmt::Result {
-    let mut dot = String::new();
-    if let Some(promoted) = promoted {
-        writeln!(dot, "digraph promoted{} {{", promoted)?;
-    } else {
-        writeln!(dot, "digraph Body {{")?;
-    }
+    let dot = build_graph(tcx, mir, breakpoints, promoted, rainbow).to_dot()?;
+    w.write_str(
+        ::std::str::from_utf8(&::cgraph::Graph::parse(dot).unwrap().render_dot().unwrap()).unwrap(),
+    )
+}
 
-    // Global graph properties
-    writeln!(dot, r#"    graph [fontname="monospace"];"#)?;
-    writeln!(dot, r#"    node [fontname="monospace"];"#)?;
-    writeln!(dot, r#"    edge [fontname="monospace"];"#)?;
+/// Serve the current frame's MIR as the serializable `Graph` model, for
+/// external tooling to consume as `/mir.json`.
+pub fn render_mir_json<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    mir: &Body,
+    breakpoints: LocalBreakpoints,
+    promoted: Option<usize>,
+) -> Json<Graph> {
+    Json(build_graph(tcx, mir, breakpoints, promoted, false))
+}
 
-    // Nodes
-    for (block, _) in mir.basic_blocks().iter_enumerated() {
-        write_node(block, mir, breakpoints, promoted, &mut dot)?;
-    }
+/// Build the graph for the current step and diff it against the graph built
+/// for the previous step (if any), then remember it as the new "previous"
+/// graph. Returns which nodes are new and which statements changed.
+pub fn render_mir_diff<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    mir: &Body,
+    breakpoints: LocalBreakpoints,
+    promoted: Option<usize>,
+) -> Vec<NodeDiff> {
+    let graph = build_graph(tcx, mir, breakpoints, promoted, false);
+    let diff = PREVIOUS_GRAPH.with(|previous| {
+        previous
+            .borrow()
+            .as_ref()
+            .map(|previous| graph.diff(previous))
+            .unwrap_or_default()
+    });
+    PREVIOUS_GRAPH.with(|previous| *previous.borrow_mut() = Some(graph));
+    diff
+}
+
+/// Build the intermediate, serializable graph model for a MIR body.
+fn build_graph<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    mir: &Body,
+    breakpoints: LocalBreakpoints,
+    promoted: Option<usize>,
+    rainbow: bool,
+) -> Graph {
+    // So that a local's color agrees with the color its source binding gets in
+    // `render::source` (which hashes the identifier text), hash the name the
+    // body's debug info gives the local rather than its `_N` form.
+    let local_names: HashMap<u32, String> = mir
+        .var_debug_info
+        .iter()
+        .filter(|info| info.place.projection.is_empty())
+        .map(|info| (info.place.local.as_u32(), info.name.to_string()))
+        .collect();
+    let file_hash = rainbow::file_hash(tcx, mir.span);
 
-    // Edges
-    for (source, _) in mir.basic_blocks().iter_enumerated() {
-        write_edges(source, mir, &mut dot)?;
+    Graph {
+        name: if let Some(promoted) = promoted {
+            format!("promoted{}", promoted)
+        } else {
+            "Body".to_string()
+        },
+        kind: match promoted {
+            Some(promoted) => GraphKind::Promoted(promoted),
+            None => GraphKind::Body,
+        },
+        // `mir.source` already bundles the instance (so two monomorphizations
+        // of the same generic function count as different bodies) and the
+        // promoted index, which is exactly the identity `Graph::diff` needs.
+        identity: format!("{:?}", mir.source),
+        nodes: mir
+            .basic_blocks()
+            .iter_enumerated()
+            .map(|(block, _)| {
+                build_node(
+                    block,
+                    mir,
+                    breakpoints,
+                    promoted,
+                    rainbow,
+                    file_hash,
+                    &local_names,
+                )
+            })
+            .collect(),
+        edges: mir
+            .basic_blocks()
+            .iter_enumerated()
+            .flat_map(|(source, _)| build_edges(source, mir))
+            .collect(),
     }
-    writeln!(dot, "}}")?;
-    w.write_str(
-        ::std::str::from_utf8(&::cgraph::Graph::parse(dot).unwrap().render_dot().unwrap()).unwrap(),
-    )
 }
 
-/// Write a graphviz HTML-styled label for the given basic block, with
-/// all necessary escaping already performed. (This is suitable for
-/// emitting directly, as is done in this module, or for use with the
-/// `LabelText::HtmlStr` from libgraphviz.)
-fn write_node_label<W: Write>(
+/// Build the node for the given basic block, with all necessary escaping
+/// already performed in its `stmts`.
+fn build_node(
     block: BasicBlock,
     mir: &Body,
     breakpoints: LocalBreakpoints,
     promoted: Option<usize>,
-    w: &mut W,
-) -> fmt::Result {
+    rainbow: bool,
+    file_hash: u64,
+    local_names: &HashMap<u32, String>,
+) -> Node {
     let data = &mir[block];
 
-    write!(w, r#"<table border="0" cellborder="1" cellspacing="0">"#)?;
-
-    // Basic block number at the top.
-    write!(
-        w,
-        r#"<tr><td bgcolor="gray" align="center">{blk}</td></tr>"#,
-        blk = node(promoted, block)
-    )?;
-
-    // List of statements in the middle.
-    if !data.statements.is_empty() {
-        write!(w, r#"<tr><td align="left" balign="left">"#)?;
-        for (statement_index, statement) in data.statements.iter().enumerate() {
-            if breakpoints.breakpoint_exists(Some(Location {
-                block,
-                statement_index,
-            })) {
-                write!(w, "+ ")?;
-            } else {
-                write!(w, "&nbsp; ")?;
-            }
-            if crate::should_hide_stmt(statement) {
-                write!(w, "&lt;+&gt;<br/>")?;
-            } else {
-                write!(w, "{}<br/>", escape(statement))?;
-            }
+    let mut stmts = Vec::with_capacity(data.statements.len() + 1);
+    let mut stmt_ids = Vec::with_capacity(data.statements.len() + 1);
+    for (statement_index, statement) in data.statements.iter().enumerate() {
+        let mut stmt = String::new();
+        if breakpoints.breakpoint_exists(Some(Location {
+            block,
+            statement_index,
+        })) {
+            stmt.push_str("+ ");
+        } else {
+            stmt.push_str("&nbsp; ");
         }
-        write!(w, "</td></tr>")?;
+        if crate::should_hide_stmt(statement) {
+            stmt.push_str("&lt;+&gt;");
+        } else if rainbow {
+            stmt.push_str(&rainbow_locals(&escape(statement), file_hash, local_names));
+        } else {
+            stmt.push_str(&escape(statement));
+        }
+        stmts.push(stmt);
+        stmt_ids.push(span_map::location_key_for(mir, block, statement_index));
     }
 
     // Terminator head at the bottom, not including the list of successor blocks. Those will be
@@ -202,51 +306,74 @@ fn write_node_label<W: Write>(
         .kind
         .fmt_head(&mut terminator_head)
         .unwrap();
-    write!(
-        w,
-        r#"<tr><td align="left">{}</td></tr>"#,
-        escape_html(&terminator_head)
-    )?;
+    let terminator_head = escape_html(&terminator_head).into_owned();
+    stmts.push(if rainbow {
+        rainbow_locals(&terminator_head, file_hash, local_names)
+    } else {
+        terminator_head
+    });
+    stmt_ids.push(span_map::location_key_for(
+        mir,
+        block,
+        data.statements.len(),
+    ));
 
-    // Close the table
-    writeln!(w, "</table>")
+    Node {
+        label: node(promoted, block),
+        style: NodeStyle::default(),
+        stmts,
+        stmt_ids,
+    }
 }
 
-/// Write a graphviz DOT node for the given basic block.
-fn write_node<W: Write>(
-    block: BasicBlock,
-    mir: &Body,
-    breakpoints: LocalBreakpoints,
-    promoted: Option<usize>,
-    w: &mut W,
-) -> fmt::Result {
-    // Start a new node with the label to follow, in one of DOT's pseudo-HTML tables.
-    write!(
-        w,
-        r#"    "{}" [shape="none", label=<"#,
-        node(promoted, block)
-    )?;
-    write_node_label(block, mir, breakpoints, promoted, w)?;
-    // Close the node label and the node itself.
-    writeln!(w, ">];")
+lazy_static::lazy_static! {
+    static ref LOCAL_RE: regex::Regex = regex::Regex::new(r"_[0-9]+").unwrap();
 }
 
-/// Write graphviz DOT edges with labels between the given basic block and all of its successors.
-fn write_edges<W: Write>(source: BasicBlock, mir: &Body, w: &mut W) -> fmt::Result {
+/// Color every `_N` local reference in `text` to match the color its source
+/// binding (if debug info names it) gets in the rendered source pane.
+///
+/// `LOCAL_RE` matches against the raw `{:?}`-escaped statement text, so it
+/// can also pick up digit runs inside string/byte-string constants the
+/// debugged program happens to print (e.g. `"_99999999999999999999"`), which
+/// don't fit in a `u32` local index. Those are left uncolored rather than
+/// unwrapped, since a local index that large can't be real.
+fn rainbow_locals(text: &str, file_hash: u64, local_names: &HashMap<u32, String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for m in LOCAL_RE.find_iter(text) {
+        out.push_str(&text[last..m.start()]);
+        match m.as_str()[1..].parse::<u32>().ok() {
+            Some(local) => {
+                let ident = local_names
+                    .get(&local)
+                    .map(String::as_str)
+                    .unwrap_or_else(|| m.as_str());
+                let color = rainbow::color_for(file_hash, ident, 0);
+                out.push_str(&rainbow::font_spanned(m.as_str(), &color));
+            }
+            None => out.push_str(m.as_str()),
+        }
+        last = m.end();
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Build the graphviz edges with labels between the given basic block and all of its successors.
+fn build_edges(source: BasicBlock, mir: &Body) -> Vec<Edge> {
     let terminator = mir[source].terminator();
     let labels = terminator.kind.fmt_successor_labels();
 
-    for (&target, label) in terminator.successors().zip(labels) {
-        writeln!(
-            w,
-            r#"    {} -> {} [label="{}"];"#,
-            node(None, source),
-            node(None, target),
-            label
-        )?;
-    }
-
-    Ok(())
+    terminator
+        .successors()
+        .zip(labels)
+        .map(|(&target, label)| Edge {
+            from: node(None, source),
+            to: node(None, target),
+            label: label.into_owned(),
+        })
+        .collect()
 }
 
 fn node(promoted: Option<usize>, block: BasicBlock) -> String {
@@ -0,0 +1,45 @@
+//! Walks a `Span`'s macro backtrace and tags each step as either the literal
+//! surface syntax the user wrote (a macro call site) or the construct it
+//! expanded into, borrowing rust-analyzer's "injection" framing: the call and
+//! its expansion are two layers of the same source location, and a debugger
+//! showing MIR generated from macro-expanded code should let the user see
+//! both instead of only the (potentially unrecognisable) expansion.
+
+use rustc_span::{ExpnKind, Span};
+
+/// One step of a macro backtrace.
+pub struct Segment {
+    pub span: Span,
+    /// `Some(macro_name)` if this segment's span is itself the *expansion* of
+    /// a `macro_name!` call made at the next, less deeply nested segment;
+    /// `None` for a segment that's literal surface syntax (including the
+    /// outermost, top-level call site).
+    pub expanded_from: Option<String>,
+}
+
+/// Collect `span`'s macro backtrace, from the literal span a MIR
+/// statement/terminator points at out to its top-level call site (which, for
+/// code that isn't inside a macro at all, is just `span` itself).
+pub fn backtrace(span: Span) -> Vec<Segment> {
+    let mut chain = vec![Segment {
+        span,
+        expanded_from: None,
+    }];
+    loop {
+        let current = chain.last().unwrap().span;
+        let expn = match current.macro_backtrace().next() {
+            Some(expn) => expn,
+            None => break,
+        };
+        let name = match expn.kind {
+            ExpnKind::Macro(_, symbol) => Some(symbol.to_string()),
+            _ => None,
+        };
+        chain.last_mut().unwrap().expanded_from = name;
+        chain.push(Segment {
+            span: expn.call_site,
+            expanded_from: None,
+        });
+    }
+    chain
+}
@@ -0,0 +1,7 @@
+pub mod graph;
+pub mod graphviz;
+pub mod injection;
+pub mod rainbow;
+pub mod source;
+pub mod span_map;
+pub mod theme;
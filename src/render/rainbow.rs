@@ -0,0 +1,96 @@
+//! Stable, per-identifier colors, modeled on rust-analyzer's binding-hash
+//! rainbow highlighting. `render::source` and `render::graphviz` both call
+//! into here so that a MIR local and the source binding it was lowered from
+//! get the same color, letting a user visually trace `_4` back to its name.
+
+use rustc_middle::ty::TyCtxt;
+use rustc_span::Span;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Keywords that `render::source`'s identifier regex will also match, but
+/// which are never bindings and so should never be rainbow-colored.
+const KEYWORDS: &[&str] = &[
+    "fn", "let", "mut", "if", "else", "match", "for", "while", "loop", "return", "break",
+    "continue", "struct", "enum", "impl", "trait", "pub", "use", "mod", "const", "static", "move",
+    "ref", "as", "in", "self", "Self", "super", "crate", "true", "false", "where", "unsafe",
+    "dyn", "async", "await", "type",
+];
+
+/// The first element of the `(file_hash, identifier_text, shadow_count)` hash
+/// tuple: a stable id for the file a span lives in, so the same color is
+/// derived regardless of which renderer is asking.
+pub fn file_hash(tcx: TyCtxt<'_>, span: Span) -> u64 {
+    let filename = tcx.sess.source_map().span_to_filename(span);
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", filename).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Whether `ident` is worth rainbow-coloring at all (as opposed to a keyword
+/// or the non-binding `_` wildcard).
+pub fn is_colorable(ident: &str) -> bool {
+    matches!(ident.chars().next(), Some(c) if c.is_alphabetic() || c == '_')
+        && ident != "_"
+        && !KEYWORDS.contains(&ident)
+}
+
+/// The lightness shared by every `color_for` call. Both `render::graphviz`
+/// and `render::source` hash `(file_hash, ident, shadow_count)` to pick a
+/// color for the same binding, so lightness has to be a fixed part of that
+/// scheme rather than a per-caller knob - otherwise the two renderers agree
+/// on hue/saturation but land on visibly different colors.
+pub const LIGHTNESS: u8 = 75;
+
+/// Picks a stable color for `ident`, following rust-analyzer's scheme of
+/// mapping the hash into hue, with saturation/lightness kept in a readable
+/// range. Returned as `#rrggbb` rather than `hsl(...)` so it can be used
+/// both in plain HTML (`style="color: ..."`) and in graphviz HTML-like
+/// labels, which don't understand `hsl()`.
+pub fn color_for(file_hash: u64, ident: &str, shadow_count: u32) -> String {
+    let mut hasher = DefaultHasher::new();
+    (file_hash, ident, shadow_count).hash(&mut hasher);
+    let hash = hasher.finish();
+
+    let hue = (hash % 361) as f64;
+    let saturation = (42 + hash / 361 % 58) as f64 / 100.0;
+    let lightness = LIGHTNESS as f64 / 100.0;
+    let (r, g, b) = hsl_to_rgb(hue, saturation, lightness);
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// `hsl(hue, saturation, lightness)` (hue in degrees, saturation/lightness in
+/// `0.0..=1.0`) to 8-bit sRGB, standard conversion.
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> (u8, u8, u8) {
+    if saturation == 0.0 {
+        let v = (lightness * 255.0).round() as u8;
+        return (v, v, v);
+    }
+    let c = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let h = hue / 60.0;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r1, g1, b1) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = lightness - c / 2.0;
+    let to_u8 = |v: f64| ((v + m) * 255.0).round() as u8;
+    (to_u8(r1), to_u8(g1), to_u8(b1))
+}
+
+/// Wrap `ident` in an inline-styled span using `color`, for plain HTML
+/// contexts (the source pane).
+pub fn spanned(ident: &str, color: &str) -> String {
+    format!(r#"<span style="color: {};">{}</span>"#, color, ident)
+}
+
+/// Wrap `ident` in a `<font color="...">` tag using `color`, for graphviz
+/// HTML-like labels - which only understand a fixed tag/attribute set and
+/// don't support `<span style="...">` at all.
+pub fn font_spanned(ident: &str, color: &str) -> String {
+    format!(r#"<font color="{}">{}</font>"#, color, ident)
+}
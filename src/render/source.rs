@@ -6,16 +6,20 @@ use rustc_middle::ty::TyCtxt;
 use rustc_mir::interpret::Frame;
 use rustc_span::Span;
 
+use crate::render::injection;
+use crate::render::rainbow;
+use crate::render::span_map;
+use crate::render::theme;
+
 use horrorshow::prelude::*;
 use syntect::easy::HighlightLines;
-use syntect::highlighting::{Style, ThemeSet};
+use syntect::highlighting::{Style, Theme};
 use syntect::html::{styled_line_to_highlighted_html, IncludeBackground};
 use syntect::parsing::SyntaxSet;
 use syntect::util::{split_at, LinesWithEndings};
 
 lazy_static::lazy_static! {
     static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_nonewlines();
-    static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
 
     static ref RUST_SOURCE: regex::Regex = regex::Regex::new("/rustc/\\w+/").unwrap();
     static ref STD_SRC: Option<String> = {
@@ -32,7 +36,7 @@ lazy_static::lazy_static! {
 }
 
 pub fn initialise_statics() {
-    let _ = (&*SYNTAX_SET, &*THEME_SET);
+    let _ = (&*SYNTAX_SET, theme::names());
 }
 
 pub fn pretty_src_path(span: Span) -> String {
@@ -60,6 +64,8 @@ pub struct HighlightCacheEntry {
 pub fn render_source(
     tcx: TyCtxt<'_>,
     frame: Option<&Frame<'_, '_, Tag, FrameData<'_>>>,
+    rainbow: bool,
+    theme_name: Option<&str>,
 ) -> Box<dyn RenderBox + Send> {
     let before_time = ::std::time::Instant::now();
 
@@ -67,49 +73,66 @@ pub fn render_source(
         return Box::new(FnRenderer::new(|_| {}));
     }
     let frame = frame.unwrap();
-    let mut instr_spans = if let Some(location) = frame.current_loc().ok() {
+    let (theme_id, theme) = theme::resolve(theme_name);
+    // Every statement/terminator in the body whose span falls in whatever
+    // file we end up rendering gets its own `data-loc`, not just the one
+    // that's currently executing - see `locations_in_file`/`mark_span`.
+    let locations = span_map::build(&frame.body);
+    let instr_span = if let Some(location) = frame.current_loc().ok() {
         let stmt = location.statement_index;
         let block = location.block;
         if stmt == frame.body[block].statements.len() {
-            vec![frame.body[block].terminator().source_info.span]
+            frame.body[block].terminator().source_info.span
         } else {
-            vec![frame.body[block].statements[stmt].source_info.span]
+            frame.body[block].statements[stmt].source_info.span
         }
     } else {
-        vec![frame.body.span]
+        frame.body.span
     };
-    // Get the original macro caller
-    while let Some(span) = instr_spans
-        .last()
-        .unwrap()
-        .macro_backtrace()
-        .next()
-        .map(|b| b.call_site)
-    {
-        instr_spans.push(span);
-    }
+    // Walk out from the literal span to its top-level macro call site (if
+    // any), so a macro-expanded statement can show both the surface syntax
+    // the user wrote and the construct it expanded into.
+    let segments = injection::backtrace(instr_span);
+    // Every segment is the call site of some expansion (for a macro, its
+    // literal `name!(args)` text) except the innermost one (processed last
+    // below, once `nesting` reaches `segment_count - 1`), which is the raw
+    // expansion result rather than a call site - that's how
+    // `injection::backtrace` builds the chain. So each of the others is
+    // searched for an argument list to highlight; a lone segment (no
+    // expansion involved at all) has nothing to hunt for.
+    let segment_count = segments.len();
 
-    let highlighted_sources = instr_spans
+    let highlighted_sources = segments
         .into_iter()
         .rev()
-        .map(|sp| {
+        .enumerate()
+        .map(|(nesting, segment)| {
+            let sp = segment.span;
             let (src, lo, hi) = match get_file_source_for_span(tcx, sp) {
                 Ok(res) => res,
-                Err(err) => return (format!("{:?}", sp), err),
+                Err(err) => return (format!("{:?}", sp), segment.expanded_from, nesting, err),
+            };
+            let macro_args = if nesting + 1 < segment_count {
+                macro_call_arg_range(&src, lo, hi)
+            } else {
+                None
             };
 
-            CACHED_HIGHLIGHTED_FILES.with(|highlight_cache| {
+            let marked = CACHED_HIGHLIGHTED_FILES.with(|highlight_cache| {
                 use std::collections::hash_map::DefaultHasher;
                 use std::hash::{Hash, Hasher};
 
                 let mut hasher = DefaultHasher::new();
                 src.hash(&mut hasher);
+                // Mix in the active theme so switching themes invalidates stale entries
+                // instead of serving highlight spans computed against the old one.
+                theme_id.hash(&mut hasher);
                 let hash = hasher.finish();
 
                 let mut cache = highlight_cache.borrow_mut();
                 let entry = cache.entry(hash).or_insert_with(|| {
                     let before_time = ::std::time::Instant::now();
-                    let highlighted = syntax_highlight(&src);
+                    let highlighted = syntax_highlight(&src, theme);
                     let after_time = ::std::time::Instant::now();
                     println!("h: {:?}", after_time - before_time);
                     HighlightCacheEntry {
@@ -117,21 +140,32 @@ pub fn render_source(
                         highlighted,
                     }
                 });
-                (
-                    pretty_src_path(sp),
-                    mark_span(&entry.string, &entry.highlighted, lo, hi),
+                mark_span(
+                    &entry.string,
+                    &entry.highlighted,
+                    lo,
+                    hi,
+                    &theme::css_color(theme::marker_color(theme)),
+                    &locations_in_file(tcx, &locations, sp),
+                    macro_args,
                 )
-            })
+            });
+            let marked = if rainbow {
+                rainbow_identifiers(&marked, rainbow::file_hash(tcx, sp))
+            } else {
+                marked
+            };
+            (pretty_src_path(sp), segment.expanded_from, nesting, marked)
         })
         .collect::<Vec<_>>();
 
     let after_time = ::std::time::Instant::now();
     println!("s: {:?}", after_time - before_time);
 
-    let style = if let Some(bg_color) = THEME_SET.themes["Solarized (dark)"].settings.background {
+    let style = if let Some(bg_color) = theme.settings.background {
         format!(
-            "background-color: #{:02x}{:02x}{:02x}; display: block;",
-            bg_color.r, bg_color.g, bg_color.b
+            "background-color: {}; display: block;",
+            theme::css_color(bg_color)
         )
     } else {
         String::new()
@@ -140,18 +174,46 @@ pub fn render_source(
     horrorshow::box_html! {
         pre {
             code(id="the_code", style=style) {
-                @ for (sp, source) in highlighted_sources {
-                    span(style = "color: aqua;") {
-                        :sp; br;
+                @ for (sp, expanded_from, nesting, source) in highlighted_sources {
+                    div(style = format!("margin-left: {}em; border-left: {}", 2 * nesting, if nesting > 0 { "2px solid orange; padding-left: 0.5em; background-color: rgba(255, 165, 0, 0.06);" } else { "none;" })) {
+                        span(style = "color: aqua;") {
+                            :sp; br;
+                        }
+                        @ if let Some(macro_name) = expanded_from {
+                            span(style = "color: orange;") {
+                                : format!("└ expanded from {}!(..)", macro_name); br;
+                            }
+                        }
+                        : Raw(source);
                     }
-                    : Raw(source);
-                    br; br;
+                    br;
                 }
             }
         }
     }
 }
 
+/// The subset of `locations` (a span -> location-keys map for the whole MIR
+/// body) that live in the same file as `sp`, converted to that file's
+/// char-offset coordinates so `mark_span` can place them alongside `lo`/`hi`.
+fn locations_in_file<'a>(
+    tcx: TyCtxt<'_>,
+    locations: &'a HashMap<Span, Vec<String>>,
+    sp: Span,
+) -> Vec<(usize, usize, &'a [String])> {
+    let source_map = tcx.sess.source_map();
+    let filename = source_map.span_to_filename(sp);
+    locations
+        .iter()
+        .filter(|(span, _)| source_map.span_to_filename(**span) == filename)
+        .map(|(span, keys)| {
+            let lo = source_map.bytepos_to_file_charpos(span.lo()).0;
+            let hi = source_map.bytepos_to_file_charpos(span.hi()).0;
+            (lo, hi, keys.as_slice())
+        })
+        .collect()
+}
+
 fn get_file_source_for_span(tcx: TyCtxt<'_>, sp: Span) -> Result<(String, usize, usize), String> {
     let source_map = tcx.sess.source_map();
     let _ = source_map.span_to_snippet(sp); // Ensure file src is loaded
@@ -172,8 +234,45 @@ fn get_file_source_for_span(tcx: TyCtxt<'_>, sp: Span) -> Result<(String, usize,
     Ok((src, lo, hi))
 }
 
-fn syntax_highlight<'a, 's>(src: &'s str) -> Vec<(Style, Range<usize>)> {
-    let theme = &THEME_SET.themes["Solarized (dark)"];
+/// Find `name!(args)`'s argument list within `file_contents[lo..hi]` (a
+/// macro call site's own span), returning its char range so `mark_span` can
+/// highlight it separately from the `name!` path. This is a delimiter-balance
+/// scan over the literal source text, not a real macro parser - it handles
+/// the ordinary `!(...)`/`![...]`/`!{...}` invocation forms, which covers
+/// everything rustc's own macro backtrace can hand us a call-site span for.
+fn macro_call_arg_range(file_contents: &str, lo: usize, hi: usize) -> Option<(usize, usize)> {
+    let chars: Vec<char> = file_contents.chars().skip(lo).take(hi - lo).collect();
+    let bang = chars.iter().position(|&c| c == '!')?;
+
+    let mut open_idx = bang + 1;
+    while chars.get(open_idx).is_some_and(|c| c.is_whitespace()) {
+        open_idx += 1;
+    }
+    let close = match chars.get(open_idx)? {
+        '(' => ')',
+        '[' => ']',
+        '{' => '}',
+        _ => return None,
+    };
+
+    let args_start = open_idx + 1;
+    let mut depth = 1u32;
+    let mut end = args_start;
+    while end < chars.len() && depth > 0 {
+        if chars[end] == chars[open_idx] {
+            depth += 1;
+        } else if chars[end] == close {
+            depth -= 1;
+        }
+        end += 1;
+    }
+    if depth != 0 {
+        return None;
+    }
+    Some((lo + args_start, lo + end - 1))
+}
+
+fn syntax_highlight<'s>(src: &'s str, theme: &Theme) -> Vec<(Style, Range<usize>)> {
     let mut h = HighlightLines::new(
         &SYNTAX_SET
             .find_syntax_by_extension("rs")
@@ -197,23 +296,122 @@ fn syntax_highlight<'a, 's>(src: &'s str) -> Vec<(Style, Range<usize>)> {
     highlighted
 }
 
-fn mark_span(file_contents: &str, src: &[(Style, Range<usize>)], lo: usize, hi: usize) -> String {
+/// Render `src` to HTML, wrapping `lo..hi` (the currently executing
+/// sub-span, may be empty for a caret) in `marker_color`, separately
+/// wrapping every range in `locations` in a `data-loc="<ids>"` span so any of
+/// those MIR cells can be looked up from - or can look up - this exact
+/// source range, and, if this is a macro call site, giving `macro_args` (the
+/// invocation's own argument list, distinct from its `name!` path) its own
+/// highlight so a macro call's arguments read differently from the call
+/// itself. `locations`/`macro_args` entries are flattened into the
+/// non-overlapping runs `split_at` already works over, each carrying
+/// whichever of them are active across it, so nested/overlapping spans (e.g.
+/// a terminator's span containing one of its operands) don't require nested
+/// markup.
+fn mark_span(
+    file_contents: &str,
+    src: &[(Style, Range<usize>)],
+    lo: usize,
+    hi: usize,
+    marker_color: &str,
+    locations: &[(usize, usize, &[String])],
+    macro_args: Option<(usize, usize)>,
+) -> String {
     let src = src
         .iter()
         .map(|(style, range)| (*style, &file_contents[range.clone()]))
         .collect::<Vec<_>>();
-    let (before, with) = split_at(&src, lo);
-    let (it, after) = split_at(&with, hi - lo);
+    let len: usize = src.iter().map(|(_, s)| s.chars().count()).sum();
+
+    let mut boundaries: Vec<usize> = vec![0, lo.min(len), hi.min(len), len];
+    for &(l, h, _) in locations {
+        boundaries.push(l.min(len));
+        boundaries.push(h.min(len));
+    }
+    if let Some((arg_lo, arg_hi)) = macro_args {
+        boundaries.push(arg_lo.min(len));
+        boundaries.push(arg_hi.min(len));
+    }
+    boundaries.sort_unstable();
+    boundaries.dedup();
 
-    let before = styled_line_to_highlighted_html(&before, IncludeBackground::No);
-    let it = styled_line_to_highlighted_html(&it, IncludeBackground::No);
-    let after = styled_line_to_highlighted_html(&after, IncludeBackground::No);
+    let mut out = String::new();
+    let mut rest = src.as_slice();
+    for window in boundaries.windows(2) {
+        let (start, end) = (window[0], window[1]);
+        if lo == hi && start == lo {
+            out.push_str(&format!(
+                "<span style='background-color: {marker_color}; border-radius: 5px; padding: 1px;'>←</span>",
+                marker_color = marker_color
+            ));
+        }
+        if start == end {
+            continue;
+        }
+        let (chunk, remaining) = split_at(rest, end - start);
+        rest = remaining;
 
-    if lo == hi {
-        assert_eq!(it.len(), 0);
-        format!("{}<span style='background-color: lightcoral; border-radius: 5px; padding: 1px;'>←</span>{}", before, after)
-    } else {
-        assert_ne!(it.len(), 0);
-        format!("{}<span style='background-color: lightcoral; border-radius: 5px; padding: 1px;'>{}</span>{}", before, it, after)
+        let html = styled_line_to_highlighted_html(&chunk, IncludeBackground::No);
+        let ids: Vec<&str> = locations
+            .iter()
+            .filter(|(l, h, _)| *l <= start && end <= *h)
+            .flat_map(|(_, _, keys)| keys.iter().map(String::as_str))
+            .collect();
+        let html = if ids.is_empty() {
+            html
+        } else {
+            format!(r#"<span data-loc="{}">{}</span>"#, ids.join(" "), html)
+        };
+        let html = match macro_args {
+            Some((arg_lo, arg_hi)) if start >= arg_lo && end <= arg_hi => format!(
+                "<span style='background-color: rgba(100, 149, 237, 0.25);' title='macro call arguments'>{}</span>",
+                html
+            ),
+            _ => html,
+        };
+        let html = if lo != hi && start >= lo && end <= hi {
+            format!(
+                "<span style='background-color: {marker_color}; border-radius: 5px; padding: 1px;'>{}</span>",
+                html,
+                marker_color = marker_color
+            )
+        } else {
+            html
+        };
+        out.push_str(&html);
+    }
+    out
+}
+
+lazy_static::lazy_static! {
+    // Matches either an HTML tag, an entity (`&nbsp;` and friends), or a bare
+    // identifier; only the identifier branch gets rainbow-colored, the rest is
+    // passed through untouched so we don't corrupt the syntect markup.
+    static ref IDENT_OR_MARKUP_RE: regex::Regex =
+        regex::Regex::new(r"<[^>]*>|&[a-zA-Z]+;|[A-Za-z_][A-Za-z0-9_]*").unwrap();
+}
+
+/// Rainbow-color every identifier in already syntax-highlighted `html`,
+/// linking it to the same color the corresponding MIR local gets in
+/// `render::graphviz::build_node`.
+///
+/// We don't track true variable shadowing here (that needs name resolution
+/// we don't have on this path), so every occurrence of a given identifier
+/// text in the file gets the same shadow count (0) and thus the same color.
+fn rainbow_identifiers(html: &str, file_hash: u64) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut last = 0;
+    for m in IDENT_OR_MARKUP_RE.find_iter(html) {
+        out.push_str(&html[last..m.start()]);
+        let text = m.as_str();
+        if text.starts_with('<') || text.starts_with('&') || !rainbow::is_colorable(text) {
+            out.push_str(text);
+        } else {
+            let color = rainbow::color_for(file_hash, text, 0);
+            out.push_str(&rainbow::spanned(text, &color));
+        }
+        last = m.end();
     }
+    out.push_str(&html[last..]);
+    out
 }
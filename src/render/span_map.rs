@@ -0,0 +1,53 @@
+//! A tiny span map, analogous to rustdoc's `span_map`: a single stable DOM id
+//! for a MIR `Location`, shared by `render::graphviz` (which sets it as the
+//! `ID` of a statement's table cell) and `render::source` (which sets it as
+//! the `data-loc` of the source range that statement's `source_info.span`
+//! came from). A click handler on either side can then look the other side up
+//! by this id to link the two panes.
+
+use std::collections::HashMap;
+
+use rustc_middle::mir::{BasicBlock, Body, Location};
+use rustc_span::Span;
+
+/// The DOM id for `location` within `body`: `bb{N}s{M}` for a statement, or
+/// `bb{N}t` for a block's terminator.
+pub fn location_key(body: &Body, location: Location) -> String {
+    if location.statement_index == body[location.block].statements.len() {
+        format!("bb{}t", location.block.index())
+    } else {
+        format!("bb{}s{}", location.block.index(), location.statement_index)
+    }
+}
+
+/// The DOM id for a statement at `statement_index` in `block`, without
+/// needing to first determine whether it's the terminator.
+pub fn location_key_for(body: &Body, block: BasicBlock, statement_index: usize) -> String {
+    location_key(
+        body,
+        Location {
+            block,
+            statement_index,
+        },
+    )
+}
+
+/// Map every statement/terminator's literal `source_info.span` to the set of
+/// location keys that share it (more than one MIR location can share the
+/// exact same span - e.g. several statements lowered from one source line),
+/// so `render::source` can tag every span in a rendered file with the MIR
+/// cells it corresponds to, not only the currently executing one.
+pub fn build(body: &Body) -> HashMap<Span, Vec<String>> {
+    let mut map: HashMap<Span, Vec<String>> = HashMap::new();
+    for (block, data) in body.basic_blocks().iter_enumerated() {
+        for (statement_index, statement) in data.statements.iter().enumerate() {
+            map.entry(statement.source_info.span)
+                .or_default()
+                .push(location_key_for(body, block, statement_index));
+        }
+        map.entry(data.terminator().source_info.span)
+            .or_default()
+            .push(location_key_for(body, block, data.statements.len()));
+    }
+    map
+}
@@ -0,0 +1,69 @@
+//! Runtime-selectable syntax highlighting themes, replacing the theme that
+//! used to be pinned to `"Solarized (dark)"` in three separate places in
+//! `render::source`. Themes come from syntect's bundled defaults plus any
+//! user `.tmTheme` files dropped in `PRIRODA_THEME_DIR` (or `./themes` if
+//! that isn't set).
+
+use std::path::PathBuf;
+use syntect::highlighting::{Color, Theme, ThemeSet};
+
+/// The theme used when nothing was explicitly picked, matching the previous
+/// hard-coded behaviour.
+pub const DEFAULT_THEME: &str = "Solarized (dark)";
+
+lazy_static::lazy_static! {
+    static ref THEMES: ThemeSet = load_themes();
+}
+
+fn theme_dir() -> PathBuf {
+    std::env::var_os("PRIRODA_THEME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("themes"))
+}
+
+fn load_themes() -> ThemeSet {
+    let mut themes = ThemeSet::load_defaults();
+    if let Ok(custom) = ThemeSet::load_from_folder(theme_dir()) {
+        themes.themes.extend(custom.themes);
+    }
+    themes
+}
+
+/// The names of every theme a user can pick from, for rendering a selector.
+pub fn names() -> Vec<&'static str> {
+    THEMES.themes.keys().map(String::as_str).collect()
+}
+
+/// Resolve a (possibly user-chosen) theme name, falling back to
+/// [`DEFAULT_THEME`] if `name` is unset or isn't a theme we know about - so a
+/// bookmarked URL/session referencing a since-removed custom theme degrades
+/// gracefully instead of panicking.
+pub fn resolve(name: Option<&str>) -> (&'static str, &'static Theme) {
+    match name.and_then(|name| THEMES.themes.get_key_value(name)) {
+        Some((name, theme)) => (name.as_str(), theme),
+        None => (
+            DEFAULT_THEME,
+            &THEMES.themes[DEFAULT_THEME],
+        ),
+    }
+}
+
+/// Derive the `mark_span` marker color from the theme's own palette - its
+/// selection background, falling back to its foreground - instead of the
+/// fixed `lightcoral`, which read as barely-there on light themes.
+pub fn marker_color(theme: &Theme) -> Color {
+    theme
+        .settings
+        .selection
+        .or(theme.settings.foreground)
+        .unwrap_or(Color {
+            r: 240,
+            g: 128,
+            b: 128,
+            a: 255,
+        })
+}
+
+pub fn css_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}